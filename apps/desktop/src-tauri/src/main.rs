@@ -3,10 +3,15 @@
 mod commands;
 
 use tauri_plugin_sql::{Builder as SqlBuilder};
-use commands::{check_for_updates, install_update, get_app_version};
+use commands::{
+    check_for_updates, recheck_for_updates, install_update, verify_update_signature,
+    get_update_capability, set_update_policy, get_app_version, UpdaterState,
+};
 
 fn main() {
     tauri::Builder::default()
+        // Long-lived updater state: in-flight guard + last-check cache
+        .manage(UpdaterState::default())
         // SQL plugin — Tauri 2, plugin v2.0 API
         .plugin(SqlBuilder::default().build())
         // Shell plugin — Tauri 2, plugin v2.0 API
@@ -16,7 +21,11 @@ fn main() {
         // Custom commands for updates
         .invoke_handler(tauri::generate_handler![
             check_for_updates,
+            recheck_for_updates,
             install_update,
+            verify_update_signature,
+            get_update_capability,
+            set_update_policy,
             get_app_version
         ])
         .run(tauri::generate_context!())